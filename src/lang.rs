@@ -13,16 +13,24 @@ use values::{Value, Range};
 use compile::{UncheckedCtx, UncheckedEnv, CompiledCtx, CompiledInput, Context, DatedData}; // FIXME: Determine exactly where these definitions should go.
 use compile;
 
-use std::collections::HashMap;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::cmp::Reverse;
+use std::mem;
 use std::sync::Arc; // FIXME: Investigate if we really need so many instances of Arc. I suspect that most can be replaced by &'a.
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, RecvTimeoutError, TryRecvError};
+use std::sync::{Condvar, Mutex};
 use std::marker::PhantomData;
 use std::result::Result;
 use std::result::Result::*;
 use std::thread;
+use std::time::Duration as StdDuration;
 
 extern crate chrono;
-use self::chrono::{DateTime, UTC};
+use self::chrono::{DateTime, Duration, UTC};
+
+extern crate rand;
+use self::rand::{Rng, SeedableRng, StdRng};
 
 
 ///
@@ -92,13 +100,71 @@ pub struct Trigger<Ctx, Env> where Env: DevEnv, Ctx: Context {
     /// Stuff to do once `condition` is met.
     pub execute: Vec<Statement<Ctx, Env>>,
 
-    /*
-    /// Minimal duration between two executions of the trigger.  If a
-    /// duration was not picked by the developer, a reasonable default
-    /// duration should be picked (e.g. 10 minutes).
-    FIXME: Implement
-    pub cooldown: Duration,
-     */
+    /// What to do if this trigger becomes ready to fire again while
+    /// its previous `execute` batch is still running. `None` means
+    /// "use whatever default policy `Execution::start` was given".
+    pub on_busy: Option<OnBusy>,
+
+    /// Minimal duration between two executions of the trigger. If a
+    /// duration was not picked by the developer, `default_cooldown()`
+    /// is used instead.
+    pub cooldown: Option<Duration>,
+
+    /// Minimal duration the condition must remain continuously true
+    /// before `execute` actually runs. `None` means "execute as soon
+    /// as the condition is met", which was the previous behavior.
+    pub debounce: Option<Duration>,
+
+    /// Bookkeeping used to throttle executions. Not part of the
+    /// script's "meaning", just runtime state.
+    pub state: TriggerState,
+}
+
+/// Per-trigger throttling state, tracking when a trigger last fired
+/// and how long its condition has been continuously true.
+#[derive(Clone, Default)]
+pub struct TriggerState {
+    /// The last time this trigger actually executed `execute`.
+    last_fired: Option<DateTime<UTC>>,
+
+    /// The time at which the condition most recently became true.
+    /// Reset to `None` whenever the condition flips back to false.
+    pending_since: Option<DateTime<UTC>>,
+}
+
+/// Default minimal duration between two executions of a trigger, used
+/// when the script does not specify `cooldown` explicitly.
+pub fn default_cooldown() -> Duration {
+    Duration::minutes(10)
+}
+
+/// What to do when a trigger becomes ready to fire again while its
+/// previous `execute` batch is still running.
+#[derive(Clone)]
+pub enum OnBusy {
+    /// Let the new firing wait until the in-flight one completes, then
+    /// run it. If several firings stack up while busy, only the most
+    /// recent one is kept.
+    Queue,
+
+    /// Drop the new firing; the in-flight actions keep running
+    /// undisturbed.
+    DoNothing,
+
+    /// Cancel the in-flight actions (skipping any statement not yet
+    /// started) and start over with the new firing.
+    Restart,
+
+    /// Leave the in-flight actions running, but invoke a user-supplied
+    /// hook so they can react (e.g. flip a cancellation flag they poll
+    /// themselves) before the new firing is dropped.
+    Signal(Arc<Fn() + Send + Sync>),
+}
+
+impl Default for OnBusy {
+    fn default() -> Self {
+        OnBusy::Queue
+    }
 }
 
 /// A conjunction (e.g. a "and") of conditions.
@@ -138,17 +204,51 @@ pub struct Statement<Ctx, Env> where Env: DevEnv, Ctx: Context {
 
 pub struct InputSet<Ctx, Env> where Env: DevEnv, Ctx: Context {
     /// The set of inputs from which to grab the value, i.e.
-    /// all the inputs matching some condition.
+    /// all the inputs matching some condition. The value grabbed is
+    /// always `condition.capability`'s: there is no watch wired up yet
+    /// to serve a different one.
+    // FIXME: Once a statement needs to grab a capability other than the
+    // one its condition watches, this will need its own `capability`
+    // field plus a matching watch registered in `start_watching`.
     pub condition: Condition<Ctx, Env>,
 
-    /// The value to grab.
-    pub capability: Env::InputCapability,
+    /// Reject a matching input's value if it is older than this, rather
+    /// than act on a possibly-stale reading. `None` means any age is
+    /// acceptable.
+    pub max_age: Option<Duration>,
+
+    /// How to combine the values of several matching inputs into one.
+    pub reduction: Reduction,
+}
+
+/// How `Expression::Input` combines the values of several inputs
+/// matching its `InputSet` into the single `Value` a statement needs.
+#[derive(Clone)]
+pub enum Reduction {
+    /// Use the value of the first matching input, in the order of the
+    /// set, that has a (non-stale) value.
+    First,
+
+    /// The smallest of the matching numeric values.
+    Min,
+
+    /// The largest of the matching numeric values.
+    Max,
+
+    /// The arithmetic mean of the matching numeric values.
+    Mean,
+}
+
+impl Default for Reduction {
+    fn default() -> Self {
+        Reduction::First
+    }
 }
 
 /// A value that may be sent to an output.
 pub enum Expression<Ctx, Env> where Env: DevEnv, Ctx: Context {
-    /// A dynamic value, which must be read from one or more inputs.
-    // FIXME: Not ready yet
+    /// A dynamic value, read from one or more inputs at the time the
+    /// statement executes. See `Expression::eval`.
     Input(InputSet<Ctx, Env>),
 
     /// A constant value.
@@ -159,18 +259,240 @@ pub enum Expression<Ctx, Env> where Env: DevEnv, Ctx: Context {
 }
 
 
+///
+/// # The runtime: time, spawning, timers
+///
+/// Every timing decision made while running a script (cooldowns,
+/// debounce, waking up for a `Tick`) goes through this abstraction
+/// instead of calling `UTC::now()` or `thread::spawn` directly, so that
+/// it can be swapped for a deterministic implementation in tests.
+///
+
+/// What `Runtime::recv` got back.
+enum RecvOutcome {
+    /// A message arrived before `deadline`.
+    Message(ExecutionOp),
+
+    /// `deadline` elapsed with nothing arriving.
+    TimedOut,
+
+    /// The sending half is gone; there is nothing left to wait for.
+    Disconnected,
+}
+
+/// Where an `ExecutableDevEnv` gets its notion of time and
+/// concurrency. `dependencies::ExecutableDevEnv` is expected to grow a
+/// `runtime() -> &'static Self::Runtime` method; until that lands, we
+/// require `Env: RuntimeEnv` directly wherever we need it.
+// FIXME: Determine exactly where this belongs -- probably folded into
+// `dependencies::ExecutableDevEnv` once this abstraction proves itself.
+pub trait RuntimeEnv: ExecutableDevEnv {
+    type Runtime: Runtime;
+
+    /// The runtime used to execute scripts for this `Env`. Production
+    /// code returns a `RealRuntime`; tests return a `DeterministicRuntime`.
+    fn runtime() -> &'static Self::Runtime;
+}
+
+/// Abstraction over "what time is it" and "how do we run things
+/// concurrently".
+pub trait Runtime: Send + Sync {
+    /// The current time, as seen by this runtime.
+    fn now(&self) -> DateTime<UTC>;
+
+    /// Run `f` concurrently with the caller.
+    fn spawn(&self, f: Box<FnMut() + Send>);
+
+    /// Wait for the next message on `rx`, waking up with `TimedOut`
+    /// at `deadline` (the "timer" half of this trait) if nothing
+    /// arrives first. `deadline: None` means wait indefinitely.
+    fn recv(&self, rx: &Receiver<ExecutionOp>, deadline: Option<DateTime<UTC>>) -> RecvOutcome;
+}
+
+/// The production `Runtime`: real wall-clock time and real threads.
+pub struct RealRuntime;
+
+impl Runtime for RealRuntime {
+    fn now(&self) -> DateTime<UTC> {
+        UTC::now()
+    }
+
+    fn spawn(&self, mut f: Box<FnMut() + Send>) {
+        thread::spawn(move || f());
+    }
+
+    fn recv(&self, rx: &Receiver<ExecutionOp>, deadline: Option<DateTime<UTC>>) -> RecvOutcome {
+        match deadline {
+            None => {
+                match rx.recv() {
+                    Ok(msg) => RecvOutcome::Message(msg),
+                    Err(_) => RecvOutcome::Disconnected,
+                }
+            }
+            Some(deadline) => {
+                let timeout = (deadline - self.now()).to_std().unwrap_or(StdDuration::new(0, 0));
+                match rx.recv_timeout(timeout) {
+                    Ok(msg) => RecvOutcome::Message(msg),
+                    Err(RecvTimeoutError::Timeout) => RecvOutcome::TimedOut,
+                    Err(RecvTimeoutError::Disconnected) => RecvOutcome::Disconnected,
+                }
+            }
+        }
+    }
+}
+
+/// A deterministic `Runtime` for tests.
+///
+/// Time only moves forward when `advance()` is called, so a test drives
+/// a script by sending `ExecutionOp`s and calling `advance()` to let
+/// cooldowns/debounce windows elapse. Messages still arrive through the
+/// real `rx`/`tx` channel, but `recv()` drains whatever is currently
+/// waiting there into a queue it shuffles with its own seeded `rng`
+/// before handing messages out one at a time -- so the order in which
+/// concurrently-produced `ExecutionOp`s (e.g. two sensors updating
+/// around the same `advance()`) get processed is controlled by `seed`
+/// rather than by scheduler timing, and a test can reproduce it.
+///
+/// `spawn` still hands `f` to a real thread, same as `RealRuntime`: the
+/// determinism this runtime buys comes entirely from controlling *time*
+/// (`now`/`recv`'s deadlines), not from serializing every spawned job
+/// onto the caller's thread. `ExecutionTask::run`'s event loop in
+/// particular only returns once `Stop` arrives, so running it inline
+/// out of `Execution::start` would hang the thread that was supposed to
+/// go on and call `advance()`.
+pub struct DeterministicRuntime {
+    inner: Mutex<DeterministicState>,
+
+    /// Notified by `advance()` whenever the virtual clock moves, so a
+    /// `recv()` blocked waiting for a deadline doesn't need to busy-poll.
+    cond: Condvar,
+}
+
+struct DeterministicState {
+    now: DateTime<UTC>,
+
+    /// Controls the shuffle order `recv()` applies to messages newly
+    /// pulled off the real channel, so that order is reproducible.
+    rng: StdRng,
+
+    /// Deadlines that `recv()` has been asked to wake up at. `advance()`
+    /// discards the ones it moves past; a waiting `recv()` does not read
+    /// this heap, it notices its deadline by re-checking `now`.
+    timers: BinaryHeap<Reverse<DateTime<UTC>>>,
+
+    /// Messages pulled off the real channel and shuffled, waiting to be
+    /// handed out one at a time by `recv()`.
+    pending: VecDeque<ExecutionOp>,
+}
+
+impl DeterministicRuntime {
+    /// Create a deterministic runtime whose virtual clock starts at
+    /// `start`, with randomized choices (where this runtime makes any)
+    /// controlled by `seed` for reproducibility.
+    pub fn new(start: DateTime<UTC>, seed: usize) -> Self {
+        DeterministicRuntime {
+            inner: Mutex::new(DeterministicState {
+                now: start,
+                rng: StdRng::from_seed(&[seed]),
+                timers: BinaryHeap::new(),
+                pending: VecDeque::new(),
+            }),
+            cond: Condvar::new(),
+        }
+    }
+
+    /// Move the virtual clock forward by `delta`, without otherwise
+    /// touching the world. Send the `ExecutionOp`s a real sensor would
+    /// have produced (if any) before or after calling this, as needed
+    /// by the scenario under test.
+    pub fn advance(&self, delta: Duration) {
+        {
+            let mut state = self.inner.lock().unwrap();
+            state.now = state.now + delta;
+            while let Some(&Reverse(deadline)) = state.timers.peek() {
+                if deadline > state.now {
+                    break;
+                }
+                state.timers.pop();
+            }
+        }
+        // Wake up anyone blocked in `recv()` so it re-checks whether its
+        // deadline has now elapsed.
+        self.cond.notify_all();
+    }
+}
+
+impl Runtime for DeterministicRuntime {
+    fn now(&self) -> DateTime<UTC> {
+        self.inner.lock().unwrap().now
+    }
+
+    fn spawn(&self, mut f: Box<FnMut() + Send>) {
+        thread::spawn(move || f());
+    }
+
+    fn recv(&self, rx: &Receiver<ExecutionOp>, deadline: Option<DateTime<UTC>>) -> RecvOutcome {
+        // Record the deadline in `timers`. Noticing that it has elapsed
+        // does not depend on this: the loop below re-checks `now()`
+        // every time it wakes up, including after a racing `advance()`.
+        if let Some(deadline) = deadline {
+            self.inner.lock().unwrap().timers.push(Reverse(deadline));
+        }
+        loop {
+            {
+                // Pull everything currently waiting on the real channel
+                // into `pending`, shuffled by our own seeded `rng`, then
+                // serve from `pending` rather than straight off `rx` --
+                // this is what makes the interleaving of messages that
+                // arrived close together reproducible across runs with
+                // the same seed, instead of depending on however the OS
+                // scheduler happened to race the senders.
+                let mut state = self.inner.lock().unwrap();
+                let mut arrived = Vec::new();
+                let disconnected = loop {
+                    match rx.try_recv() {
+                        Ok(msg) => arrived.push(msg),
+                        Err(TryRecvError::Empty) => break false,
+                        Err(TryRecvError::Disconnected) => break true,
+                    }
+                };
+                state.rng.shuffle(&mut arrived);
+                state.pending.extend(arrived);
+                if let Some(msg) = state.pending.pop_front() {
+                    return RecvOutcome::Message(msg);
+                }
+                if disconnected {
+                    return RecvOutcome::Disconnected;
+                }
+            }
+            if let Some(deadline) = deadline {
+                if self.now() >= deadline {
+                    return RecvOutcome::TimedOut;
+                }
+            }
+            // Genuinely block rather than spin: wait for `advance()` to
+            // notify us, with a short real-time poll as a safety net so
+            // a message sent without a matching `advance()` call (e.g.
+            // `Stop`) is still noticed promptly.
+            let state = self.inner.lock().unwrap();
+            let _ignored = self.cond.wait_timeout(state, StdDuration::from_millis(5)).unwrap();
+        }
+    }
+}
+
+
 ///
 /// # Launching and running the script
 ///
 
 
 /// Running and controlling a single script.
-pub struct Execution<Env> where Env: ExecutableDevEnv + 'static {
+pub struct Execution<Env> where Env: RuntimeEnv + 'static {
     command_sender: Option<Sender<ExecutionOp>>,
     phantom: PhantomData<Env>,
 }
 
-impl<Env> Execution<Env> where Env: ExecutableDevEnv + 'static {
+impl<Env> Execution<Env> where Env: RuntimeEnv + 'static {
     pub fn new() -> Self {
         Execution {
             command_sender: None,
@@ -180,10 +502,13 @@ impl<Env> Execution<Env> where Env: ExecutableDevEnv + 'static {
 
     /// Start executing the script.
     ///
+    /// `default_on_busy` is the policy used for any trigger whose
+    /// `on_busy` field is `None`.
+    ///
     /// # Errors
     ///
     /// Produces RunningError:AlreadyRunning if the script is already running.
-    pub fn start<F>(&mut self, script: Script<UncheckedCtx, UncheckedEnv>, on_result: F) where F: FnOnce(Result<(), Error>) + Send + 'static {
+    pub fn start<F>(&mut self, script: Script<UncheckedCtx, UncheckedEnv>, default_on_busy: OnBusy, on_result: F) where F: FnOnce(Result<(), Error>) + Send + 'static {
         if self.command_sender.is_some() {
             on_result(Err(Error::RunningError(RunningError::AlreadyRunning)));
             return;
@@ -191,8 +516,11 @@ impl<Env> Execution<Env> where Env: ExecutableDevEnv + 'static {
         let (tx, rx) = channel();
         let tx2 = tx.clone();
         self.command_sender = Some(tx);
-        thread::spawn(move || {
-            match ExecutionTask::<Env>::new(script, tx2, rx) {
+        // `job` is really an FnOnce; wrapped in an `Option` so the
+        // `FnMut` that `Runtime::spawn` expects can still only ever
+        // call it the one time it actually runs.
+        let mut job = Some(move || {
+            match ExecutionTask::<Env>::new(script, default_on_busy, tx2, rx) {
                 Err(er) => {
                     on_result(Err(er));
                 },
@@ -202,9 +530,32 @@ impl<Env> Execution<Env> where Env: ExecutableDevEnv + 'static {
                 }
             }
         });
+        Env::runtime().spawn(Box::new(move || {
+            if let Some(job) = job.take() {
+                job();
+            }
+        }));
     }
 
 
+    /// Swap in a newly-edited version of the running script, without
+    /// restarting it: watches and cached sensor data are preserved for
+    /// anything that didn't change. See `ExecutionTask::reconfigure`
+    /// for the details and limitations of what gets carried forward.
+    ///
+    /// # Errors
+    ///
+    /// Produces RunningError:NotRunning if the script is not running yet,
+    /// or a CompileError if `new_script` does not compile.
+    pub fn reconfigure<F>(&mut self, new_script: Script<UncheckedCtx, UncheckedEnv>, on_result: F) where F: Fn(Result<(), Error>) + Send + 'static {
+        match self.command_sender {
+            None => on_result(Err(Error::RunningError(RunningError::NotRunning))),
+            Some(ref tx) => {
+                let _ignored = tx.send(ExecutionOp::Reconfigure(Box::new(new_script), Box::new(on_result)));
+            }
+        }
+    }
+
     /// Stop executing the script, asynchronously.
     ///
     /// # Errors
@@ -225,7 +576,7 @@ impl<Env> Execution<Env> where Env: ExecutableDevEnv + 'static {
     }
 }
 
-impl<Env> Drop for Execution<Env> where Env: ExecutableDevEnv + 'static {
+impl<Env> Drop for Execution<Env> where Env: RuntimeEnv + 'static {
     fn drop(&mut self) {
         let _ignored = self.stop(|_ignored| { });
     }
@@ -237,9 +588,66 @@ pub struct ExecutionTask<Env> where Env: DevEnv {
     /// The current state of execution the script.
     state: Script<CompiledCtx<Env>, Env>,
 
+    /// The `on_busy` policy used for triggers that don't specify their own.
+    default_on_busy: OnBusy,
+
+    /// The in-flight `execute` batch for each trigger, indexed like
+    /// `state.rules`. `None` means the trigger is currently idle.
+    in_flight: Vec<Option<InFlight>>,
+
+    /// `state.rules[i].execute`, moved out and shared behind an `Arc` so
+    /// `fire` can hand a cheap clone to the thread that actually runs
+    /// it, without requiring `Statement` to be `Clone` or `compile.rs`
+    /// to know anything about it. Indexed like `state.rules`; rebuilt
+    /// by `new` and by `reconfigure` whenever `state` is replaced.
+    execute: Vec<Arc<Vec<Statement<CompiledCtx<Env>, Env>>>>,
+
     /// Communicating with the thread running script.
     tx: Sender<ExecutionOp>,
     rx: Receiver<ExecutionOp>,
+
+    /// A thread-safe indirection towards each input's latest known
+    /// value, indexed like `witnesses` and `watched`. We assume that
+    /// this never mutates again once `start_watching` has returned --
+    /// except across a `reconfigure`, which atomically rebuilds all
+    /// three together.
+    cells: Vec<Arc<CompiledInput<Env>>>,
+
+    /// The (device, capability, range) watched by each entry of
+    /// `witnesses`/`cells`, kept so `reconfigure` can diff the new
+    /// watch list against this one.
+    watched: Vec<(Env::Device, Env::InputCapability, Range)>,
+
+    /// Kept alive for as long as we want to keep watching; dropping a
+    /// witness stops watching the corresponding input. Empty until
+    /// `start_watching` is called.
+    witnesses: Vec<<Env::Watcher as Watcher>::Witness>,
+
+    /// The watcher we registered `witnesses` with, kept around so that
+    /// `reconfigure` can reuse it instead of asking `Env` for a new
+    /// one. `None` until `start_watching` is called.
+    watcher: Option<Env::Watcher>,
+
+    /// Source of the `token` handed to each new `InFlight`, so a late
+    /// `Completed` from a batch that was since cancelled/superseded
+    /// (restarted in place, or orphaned by a `reconfigure`) can be told
+    /// apart from the batch actually occupying `in_flight[index]` now.
+    next_token: usize,
+}
+
+/// Bookkeeping for a trigger's currently running `execute` batch.
+struct InFlight {
+    /// Set to `true` to ask the running batch to stop before its next statement.
+    cancelled: Arc<AtomicBool>,
+
+    /// Set when another firing happened while this batch was running
+    /// and the trigger's policy is `OnBusy::Queue`, so the task knows
+    /// to run `execute` again once this batch completes.
+    queued: bool,
+
+    /// Identifies this particular batch, so `Completed` can check it is
+    /// still the one `in_flight[index]` refers to before acting on it.
+    token: usize,
 }
 
 
@@ -251,54 +659,124 @@ enum ExecutionOp {
     /// ready to be executed.
     Update {index: usize, updated: DateTime<UTC>, value: Value},
 
+    /// No input was updated, but a debounce or cooldown deadline may
+    /// have elapsed, so it is time to re-check triggers anyway.
+    Tick,
+
+    /// A trigger's `execute` batch, spawned by `ExecutionTask::fire`,
+    /// has finished running (or was cancelled). `token` identifies
+    /// which batch this is, since by the time it arrives `in_flight[index]`
+    /// may already refer to a different (restarted, or post-`reconfigure`)
+    /// batch, or to no batch at all.
+    Completed {index: usize, token: usize},
+
     /// Time to stop executing the script.
-    Stop(Box<Fn(Result<(), Error>) + Send>)
+    Stop(Box<Fn(Result<(), Error>) + Send>),
+
+    /// Swap in a newly-edited version of the script without losing
+    /// already-registered watches or cached input data, as requested
+    /// through the Web UX.
+    Reconfigure(Box<Script<UncheckedCtx, UncheckedEnv>>, Box<Fn(Result<(), Error>) + Send>),
 }
 
+/// Move each rule's `execute` batch out of `script` and into its own
+/// `Arc`, leaving `script.rules[..].execute` empty. Shared between
+/// `ExecutionTask::new` and `ExecutionTask::reconfigure`, the two
+/// places that take ownership of a freshly-compiled `Script`.
+fn take_execute_batches<Env>(script: &mut Script<CompiledCtx<Env>, Env>) -> Vec<Arc<Vec<Statement<CompiledCtx<Env>, Env>>>>
+    where Env: DevEnv
+{
+    script.rules.iter_mut()
+        .map(|rule| Arc::new(mem::replace(&mut rule.execute, Vec::new())))
+        .collect()
+}
+
+/// What `ExecutionTask::step` did.
+enum StepOutcome {
+    /// `Stop` was processed; the caller should not call `step` again.
+    Stopped,
+
+    /// Nothing was ready (non-blocking `step` only).
+    Idle,
+
+    /// A message was handled.
+    Processed,
+}
+
+/// What a quantum of `ExecutionTask::run_quantum` accomplished; read by
+/// `Supervisor::work` to decide whether to requeue the script right
+/// away or let it nap first.
+enum QuantumOutcome {
+    /// `Stop` was processed; drop the script.
+    Stopped,
+
+    /// At least one `ExecutionOp` was processed.
+    Ran,
+
+    /// Nothing was ready. Carries the same deadline `next_wake_up`
+    /// would hand a blocking `step`, if any.
+    Idle(Option<DateTime<UTC>>),
+}
 
-impl<Env> ExecutionTask<Env> where Env: ExecutableDevEnv {
+
+impl<Env> ExecutionTask<Env>
+    where Env: RuntimeEnv, Env::Device: Clone + PartialEq, Env::InputCapability: Clone + PartialEq, Range: Clone + PartialEq
+{
     /// Create a new execution task.
     ///
     /// The caller is responsible for spawning a new thread and
     /// calling `run()`.
-    fn new(script: Script<UncheckedCtx, UncheckedEnv>, tx: Sender<ExecutionOp>, rx: Receiver<ExecutionOp>) -> Result<Self, Error> {
+    fn new(script: Script<UncheckedCtx, UncheckedEnv>, default_on_busy: OnBusy, tx: Sender<ExecutionOp>, rx: Receiver<ExecutionOp>) -> Result<Self, Error> {
         // Prepare the script for execution:
         // - replace instances of Input with InputDev, which map
         //   to a specific device and cache the latest known value
         //   on the input.
         // - replace instances of Output with OutputDev
         let precompiler = try!(compile::Precompiler::new(&script).map_err(|err| Error::CompileError(err)));
-        let bound = try!(precompiler.rebind_script(script).map_err(|err| Error::CompileError(err)));
-        
+        let mut bound = try!(precompiler.rebind_script(script).map_err(|err| Error::CompileError(err)));
+
+        let in_flight = bound.rules.iter().map(|_| None).collect();
+        let execute = take_execute_batches(&mut bound);
+
         Ok(ExecutionTask {
             state: bound,
+            default_on_busy: default_on_busy,
+            in_flight: in_flight,
+            execute: execute,
             rx: rx,
-            tx: tx
+            tx: tx,
+            cells: Vec::new(),
+            watched: Vec::new(),
+            witnesses: Vec::new(),
+            watcher: None,
+            next_token: 0,
         })
     }
 
-    /// Execute the monitoring task.
-    /// This currently expects to be executed in its own thread.
-    fn run(&mut self) {
-        let mut watcher = Env::get_watcher();
-        let mut witnesses = Vec::new();
-        
-        // A thread-safe indirection towards a single input state.
-        // We assume that `cells` never mutates again once we
-        // have finished the loop below.
-        let mut cells : Vec<Arc<CompiledInput<Env>>> = Vec::new();
-
-        // Start listening to all inputs that appear in conditions.
-        // Some inputs may appear only in expressions, so we are
-        // not interested in their value.
+    /// Start listening to all inputs that appear in conditions, using
+    /// `watcher` to register them. Some inputs may appear only in
+    /// expressions, so we are not interested in their value.
+    ///
+    /// `watcher` may be shared with other `ExecutionTask`s (see
+    /// `Supervisor`), in which case duplicate (device, capability,
+    /// range) watches across scripts -- not just within this one -- get
+    /// coalesced, since that is already `Env::Watcher`'s job.
+    ///
+    /// A no-op if already watching.
+    fn start_watching(&mut self, mut watcher: Env::Watcher) {
+        if self.watcher.is_some() {
+            return;
+        }
+
         for rule in &self.state.rules  {
             for condition in &rule.condition.all {
                 for single in &*condition.input {
                     let tx = self.tx.clone();
-                    cells.push(single.clone());
-                    let index = cells.len() - 1;
+                    self.cells.push(single.clone());
+                    let index = self.cells.len() - 1;
+                    self.watched.push((single.device.clone(), condition.capability.clone(), condition.range.clone()));
 
-                    witnesses.push(
+                    self.witnesses.push(
                         // We can end up watching several times the
                         // same device + capability + range.  For the
                         // moment, we do not attempt to optimize
@@ -317,7 +795,7 @@ impl<Env> ExecutionTask<Env> where Env: ExecutableDevEnv {
                                 // whether there is anything we need
                                 // to do.
                                 let _ignored = tx.send(ExecutionOp::Update {
-                                    updated: UTC::now(),
+                                    updated: Env::runtime().now(),
                                     value: value,
                                     index: index
                                 });
@@ -327,64 +805,568 @@ impl<Env> ExecutionTask<Env> where Env: ExecutableDevEnv {
             }
         }
 
-        // Make sure that the vector never mutates past this
-        // point. This ensures that our `index` remains valid for the
-        // rest of the execution.
-        let cells = cells;
-
         // FIXME: We are going to end up with stale data in some inputs.
         // We need to find out how to get rid of it.
         // FIXME(2): We now have dates.
 
-        // Now, start handling events.
-        for msg in &self.rx {
-            use self::ExecutionOp::*;
-            match msg {
-                Stop(f) => {
-                    // Leave the loop.
-                    // The watcher and the witnesses will be cleaned up on exit.
-                    // Any further message will be ignored.
-                    f(Ok(()));
-                    return;
+        self.watcher = Some(watcher);
+    }
+
+    /// Execute the monitoring task.
+    /// This currently expects to be executed in its own thread.
+    fn run(&mut self) {
+        self.start_watching(Env::get_watcher());
+
+        // We cannot simply iterate over `self.rx`, as a trigger
+        // waiting on its debounce or cooldown window needs to be
+        // re-examined even if no new input ever arrives, so we wake
+        // ourselves up with a `Tick` once that window elapses.
+        loop {
+            if let StepOutcome::Stopped = self.step(true) {
+                return;
+            }
+        }
+    }
+
+    /// Process up to `quantum` pending `ExecutionOp`s without
+    /// blocking, yielding to the caller as soon as there is nothing
+    /// left to do. Used by `Supervisor`'s worker pool, which time-
+    /// slices many scripts across a bounded pool of workers instead of
+    /// dedicating a thread to each of them.
+    fn run_quantum(&mut self, quantum: usize) -> QuantumOutcome {
+        self.start_watching(Env::get_watcher());
+
+        let mut ran = false;
+        for _ in 0..quantum {
+            match self.step(false) {
+                StepOutcome::Stopped => return QuantumOutcome::Stopped,
+                StepOutcome::Idle => break,
+                StepOutcome::Processed => ran = true,
+            }
+        }
+        if ran {
+            QuantumOutcome::Ran
+        } else {
+            QuantumOutcome::Idle(self.next_wake_up())
+        }
+    }
+
+    /// Wait for (if `blocking`) or poll for (otherwise) the next
+    /// `ExecutionOp` and handle it.
+    fn step(&mut self, blocking: bool) -> StepOutcome {
+        use self::ExecutionOp::*;
+
+        let msg = if blocking {
+            let deadline = self.next_wake_up();
+            match Env::runtime().recv(&self.rx, deadline) {
+                RecvOutcome::Message(msg) => msg,
+                RecvOutcome::TimedOut => Tick,
+                RecvOutcome::Disconnected => return StepOutcome::Stopped,
+            }
+        } else {
+            match self.rx.try_recv() {
+                Ok(msg) => msg,
+                Err(TryRecvError::Empty) => {
+                    // Nothing arrived, but a debounce/cooldown deadline
+                    // may have elapsed since the last look.
+                    match self.next_wake_up() {
+                        Some(deadline) if deadline <= Env::runtime().now() => Tick,
+                        _ => return StepOutcome::Idle,
+                    }
+                }
+                Err(TryRecvError::Disconnected) => return StepOutcome::Stopped,
+            }
+        };
+
+        match msg {
+            Stop(f) => {
+                // The watcher and the witnesses will be cleaned up on exit.
+                // Any further message will be ignored.
+                f(Ok(()));
+                StepOutcome::Stopped
+            }
+
+            Update {updated: _, value, index} => {
+                let cell = &self.cells[index];
+                *cell.state.write().unwrap() = Some(DatedData {
+                    updated: Env::runtime().now(),
+                    data: value
+                });
+                // Note that we can unwrap() safely,
+                // as it fails only if the thread is
+                // already in panic.
+
+                self.check_triggers();
+                StepOutcome::Processed
+            }
+
+            Tick => {
+                // Nothing changed, but a debounce/cooldown deadline
+                // may have elapsed in the meantime.
+                self.check_triggers();
+                StepOutcome::Processed
+            }
+
+            Completed {index, token} => {
+                // `index` may be out of range (a `reconfigure` since
+                // this batch started may have shrunk `state.rules`), or
+                // may now refer to a batch other than the one that just
+                // finished (restarted in place, or newly fired after a
+                // `reconfigure` reset `in_flight`). Either way, `token`
+                // not matching means this `Completed` is stale and
+                // should be ignored.
+                let is_current = self.in_flight.get(index)
+                    .and_then(|slot| slot.as_ref())
+                    .map_or(false, |running| running.token == token);
+                if is_current {
+                    let queued = self.in_flight[index].take().map_or(false, |running| running.queued);
+                    if queued {
+                        // Don't just re-fire: the condition may no
+                        // longer hold (or cooldown/debounce may not
+                        // have elapsed again yet), so re-run the same
+                        // checks a fresh `Update`/`Tick` would.
+                        self.check_triggers();
+                    }
+                }
+                StepOutcome::Processed
+            }
+
+            Reconfigure(new_script, f) => {
+                f(self.reconfigure(*new_script));
+                StepOutcome::Processed
+            }
+        }
+    }
+
+    /// Replace the script being executed by `new_script`, without
+    /// restarting: watches for (device, capability, range) triples that
+    /// are unchanged carry their `DatedData` forward, and conditions
+    /// that match positionally (same rule index, same condition index)
+    /// carry forward `is_met` and the trigger's `last_fired`/
+    /// `pending_since` too, so reconfiguring a script because the user
+    /// tweaked a threshold in the Web UX doesn't re-open a watch gap,
+    /// forget the last measurement, or reset every other trigger's
+    /// cooldown/debounce clock.
+    ///
+    /// Any in-flight `execute` batch from the previous configuration is
+    /// left to finish on its own: `self.in_flight` is reset to match the
+    /// new rule count, so its eventual `Completed` either lands on an
+    /// unrelated (or absent) slot and is ignored, rather than being
+    /// mistaken for (or out-of-bounds against) a new firing, thanks to
+    /// `Completed`'s token check.
+    ///
+    /// # Limitations
+    ///
+    /// `is_met`/`last_fired`/`pending_since` are only carried forward by
+    /// matching rule/condition index, not by comparing the conditions
+    /// themselves: reordering rules (as opposed to editing or appending
+    /// them) will lose this state for the reordered rules.
+    fn reconfigure(&mut self, new_script: Script<UncheckedCtx, UncheckedEnv>) -> Result<(), Error> {
+        let precompiler = try!(compile::Precompiler::new(&new_script).map_err(|err| Error::CompileError(err)));
+        let mut bound = try!(precompiler.rebind_script(new_script).map_err(|err| Error::CompileError(err)));
+
+        // Snapshot the `is_met` of every condition, and the `state` of
+        // every trigger, that we are currently tracking -- keyed by
+        // rule index (and, for conditions, condition index) so we can
+        // copy them into `bound` below.
+        let old_is_met: Vec<Vec<bool>> = self.state.rules.iter()
+            .map(|rule| rule.condition.all.iter().map(|condition| condition.state.is_met).collect())
+            .collect();
+        let old_trigger_state: Vec<TriggerState> = self.state.rules.iter()
+            .map(|rule| rule.state.clone())
+            .collect();
+
+        for (rule_index, rule) in bound.rules.iter_mut().enumerate() {
+            if let Some(old_conditions) = old_is_met.get(rule_index) {
+                for (condition_index, condition) in rule.condition.all.iter_mut().enumerate() {
+                    if let Some(&is_met) = old_conditions.get(condition_index) {
+                        condition.state.is_met = is_met;
+                    }
                 }
+            }
+            if let Some(state) = old_trigger_state.get(rule_index) {
+                rule.state = state.clone();
+            }
+        }
+
+        // Snapshot the old (device, capability, range) -> cached value
+        // mapping, then rebuild `cells`/`watched`/`witnesses` from
+        // scratch against `bound`, so witness closures capturing
+        // `index` stay in sync with `cells`.
+        let old_watched = mem::replace(&mut self.watched, Vec::new());
+        let old_cells = mem::replace(&mut self.cells, Vec::new());
+        // Old witnesses stay alive until the new ones are registered,
+        // so unchanged watches never go unwatched in between.
+        let old_witnesses = mem::replace(&mut self.witnesses, Vec::new());
+
+        self.execute = take_execute_batches(&mut bound);
+        self.state = bound;
+        self.in_flight = self.state.rules.iter().map(|_| None).collect();
+
+        let watcher = self.watcher.take().expect("reconfigure called before start_watching");
+        self.start_watching(watcher);
+        drop(old_witnesses);
+
+        for (new_index, watched) in self.watched.iter().enumerate() {
+            if let Some(old_index) = old_watched.iter().position(|old| old == watched) {
+                // Copied rather than moved: several new cells may watch
+                // the same triple and must all get the old cell's data.
+                let data = old_cells[old_index].state.read().unwrap().as_ref().map(|data| DatedData {
+                    updated: data.updated,
+                    data: data.data.clone(),
+                });
+                *self.cells[new_index].state.write().unwrap() = data;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Find out which triggers are ready to run and execute them.
+    ///
+    /// A trigger whose condition just became true starts a debounce
+    /// window (`Trigger::debounce`); it only executes once that window
+    /// has elapsed with the condition still true, and only if at least
+    /// `cooldown` (or `default_cooldown()`) has passed since it last
+    /// fired. The condition is re-checked on every call, so a trigger
+    /// may fire again later if its condition is still true once its
+    /// cooldown expires.
+    ///
+    /// A trigger whose previous `execute` batch is still running is
+    /// handled according to its `on_busy` policy (or
+    /// `self.default_on_busy`) instead of being fired again directly.
+    fn check_triggers(&mut self) {
+        let now = Env::runtime().now();
+        let mut to_fire = Vec::new();
+        let mut to_restart = Vec::new();
+        let mut to_signal = Vec::new();
+
+        for (index, rule) in self.state.rules.iter_mut().enumerate() {
+            let is_met = rule.is_met();
+            if !is_met.new {
+                // The condition no longer holds: forget how long it
+                // used to hold for.
+                rule.state.pending_since = None;
+                continue;
+            }
+
+            let pending_since = *rule.state.pending_since.get_or_insert(now);
+            let debounce = rule.debounce.unwrap_or_else(Duration::zero);
+            if now - pending_since < debounce {
+                // Still waiting for the condition to settle.
+                continue;
+            }
+
+            let cooldown = rule.cooldown.unwrap_or_else(default_cooldown);
+            if let Some(last_fired) = rule.state.last_fired {
+                if now - last_fired < cooldown {
+                    // Fired too recently; try again once cooldown expires.
+                    continue;
+                }
+            }
+
+            if self.in_flight[index].is_none() {
+                rule.state.last_fired = Some(now);
+                to_fire.push(index);
+                continue;
+            }
+
+            // The previous batch is still running: defer to on_busy.
+            // `last_fired` is only touched by branches that actually
+            // start a fresh `execute` batch (`Restart`, and `Queue`/
+            // `to_fire` once the in-flight one completes) -- `DoNothing`
+            // and `Signal` don't run anything, so they must not extend
+            // the cooldown window past what the script configured.
+            match *rule.on_busy.as_ref().unwrap_or(&self.default_on_busy) {
+                OnBusy::Queue => {
+                    if let Some(ref mut running) = self.in_flight[index] {
+                        running.queued = true;
+                    }
+                }
+                OnBusy::DoNothing => {}
+                OnBusy::Restart => {
+                    rule.state.last_fired = Some(now);
+                    to_restart.push(index);
+                }
+                OnBusy::Signal(_) => {
+                    to_signal.push(index);
+                }
+            }
+        }
+
+        for index in to_signal {
+            if let OnBusy::Signal(ref hook) = *self.state.rules[index].on_busy.as_ref().unwrap_or(&self.default_on_busy) {
+                hook();
+            }
+        }
+        for index in to_restart {
+            if let Some(running) = self.in_flight[index].take() {
+                running.cancelled.store(true, Ordering::Relaxed);
+            }
+            self.fire(index);
+        }
+        for index in to_fire {
+            self.fire(index);
+        }
+    }
+
+    /// Spawn `self.execute[index]` as a cancellable unit through
+    /// `Env::runtime()`, recording its handle in `self.in_flight[index]`
+    /// under a fresh token.
+    fn fire(&mut self, index: usize) {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let my_cancelled = cancelled.clone();
+        let execute = self.execute[index].clone();
+        let tx = self.tx.clone();
+        let token = self.next_token;
+        self.next_token += 1;
+        // Same "FnOnce behind an FnMut" trick as `Execution::start`.
+        let mut job = Some(move || {
+            // Every statement (and every `Expression::Input` within it)
+            // judges staleness against the same instant.
+            let ctx = EvalCtx::new(Env::runtime().now());
+            for statement in execute.iter() {
+                if my_cancelled.load(Ordering::Relaxed) {
+                    break;
+                }
+                let _ignored = statement.eval(&ctx); // FIXME: Log errors
+            }
+            let _ignored = tx.send(ExecutionOp::Completed {index: index, token: token});
+        });
+        Env::runtime().spawn(Box::new(move || {
+            if let Some(job) = job.take() {
+                job();
+            }
+        }));
+        self.in_flight[index] = Some(InFlight {
+            cancelled: cancelled,
+            queued: false,
+            token: token,
+        });
+    }
+
+    /// The earliest time at which a pending trigger's debounce or
+    /// cooldown deadline elapses, if any. Used to schedule a `Tick`
+    /// when no other input arrives first.
+    fn next_wake_up(&self) -> Option<DateTime<UTC>> {
+        let mut earliest = None;
+        for rule in &self.state.rules {
+            let pending_since = match rule.state.pending_since {
+                None => continue,
+                Some(t) => t,
+            };
+            let debounce_deadline = pending_since + rule.debounce.unwrap_or_else(Duration::zero);
+            let deadline = match rule.state.last_fired {
+                None => debounce_deadline,
+                Some(last_fired) => {
+                    let cooldown_deadline = last_fired + rule.cooldown.unwrap_or_else(default_cooldown);
+                    if debounce_deadline > cooldown_deadline { debounce_deadline } else { cooldown_deadline }
+                }
+            };
+            earliest = Some(match earliest {
+                None => deadline,
+                Some(e) if deadline < e => deadline,
+                Some(e) => e,
+            });
+        }
+        earliest
+    }
+}
+
+///
+/// # Running many scripts on a bounded worker pool
+///
+
+/// Opaque identifier for a script installed in a `Supervisor`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ScriptId(usize);
+
+/// Runs many scripts on a bounded, throttled worker pool instead of
+/// giving each its own OS thread, which does not scale to the dozens
+/// of monitors a FoxBox may end up with installed at once.
+///
+/// Concurrency is capped at a fixed number of workers: at most that
+/// many scripts process their `ExecutionOp`s at once. The `execute`
+/// batches their triggers fire are spawned through `Env::runtime()`
+/// and run outside the workers, so they do not count against this
+/// cap. A
+/// worker only processes a small, time-sliced batch of a script's
+/// ready `ExecutionOp`s before putting it back at the end of the run
+/// queue, so no single chatty sensor can starve the other installed
+/// scripts.
+///
+pub struct Supervisor<Env> where Env: RuntimeEnv + 'static {
+    next_id: usize,
+
+    /// Shared across every installed script so that duplicate
+    /// (device, capability, range) watches are coalesced by `Env`'s
+    /// watcher implementation, instead of each script registering its
+    /// own independent subscription.
+    watcher: Env::Watcher,
+
+    /// Each script behind its own lock, so the map lock only needs to
+    /// be held long enough to look up (or insert/remove) a script's
+    /// handle -- never for the duration of a `run_quantum`, which would
+    /// otherwise serialize every worker on this one lock and defeat the
+    /// whole point of having more than one of them.
+    scripts: Arc<Mutex<HashMap<ScriptId, Arc<Mutex<ExecutionTask<Env>>>>>>,
+    run_queue: Arc<(Mutex<VecDeque<ScriptId>>, Condvar)>,
+    stop: Arc<AtomicBool>,
+}
+
+impl<Env> Supervisor<Env> where Env: RuntimeEnv + 'static {
+    /// Create a supervisor backed by `concurrency` workers, each
+    /// processing up to `quantum` `ExecutionOp`s of a script per turn
+    /// before yielding to the next one in the queue.
+    pub fn new(concurrency: usize, quantum: usize) -> Self {
+        let scripts: Arc<Mutex<HashMap<ScriptId, Arc<Mutex<ExecutionTask<Env>>>>>> = Arc::new(Mutex::new(HashMap::new()));
+        let run_queue = Arc::new((Mutex::new(VecDeque::new()), Condvar::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        for _ in 0..concurrency {
+            let scripts = scripts.clone();
+            let run_queue = run_queue.clone();
+            let stop = stop.clone();
+            // Same "FnOnce behind an FnMut" trick as `Execution::start`:
+            // go through `Env::runtime()` rather than a raw
+            // `thread::spawn`, so a `DeterministicRuntime` can stand in
+            // for workers in tests the same way it does for a single
+            // `ExecutionTask`.
+            let mut job = Some(move || Supervisor::<Env>::work(scripts, run_queue, stop, quantum));
+            Env::runtime().spawn(Box::new(move || {
+                if let Some(job) = job.take() {
+                    job();
+                }
+            }));
+        }
+
+        Supervisor {
+            next_id: 0,
+            watcher: Env::get_watcher(),
+            scripts: scripts,
+            run_queue: run_queue,
+            stop: stop,
+        }
+    }
+
+    /// Install `script`, sharing this supervisor's watcher with it,
+    /// and make it eligible to run. `on_result` mirrors
+    /// `Execution::start`'s callback: it reports whether `script`
+    /// compiled and was installed, not how it eventually fares.
+    pub fn install<F>(&mut self, script: Script<UncheckedCtx, UncheckedEnv>, on_result: F) -> Option<ScriptId>
+        where F: FnOnce(Result<(), Error>) + Send + 'static
+    {
+        let (tx, rx) = channel();
+        let mut task = match ExecutionTask::<Env>::new(script, OnBusy::default(), tx, rx) {
+            Err(er) => {
+                on_result(Err(er));
+                return None;
+            }
+            Ok(task) => task,
+        };
+        task.start_watching(self.watcher.clone());
 
-                Update {updated, value, index} => {
-                    let cell = &cells[index];
-                    *cell.state.write().unwrap() = Some(DatedData {
-                        updated: UTC::now(),
-                        data: value
-                    });
-                    // Note that we can unwrap() safely,
-                    // as it fails only if the thread is
-                    // already in panic.
-
-                    // Find out if we should execute triggers.
-                    for mut rule in &mut self.state.rules {
-                        let is_met = rule.is_met();
-                        if !(is_met.new && !is_met.old) {
-                            // We should execute the trigger only if
-                            // it was false and is now true. Here,
-                            // either it was already true or it isn't
-                            // false yet.
-                            continue;
-                        }
-
-                        // Conditions were not met, now they are, so
-                        // it is time to start executing.
-
-                        // FIXME: We do not want triggers to be
-                        // triggered too often. Handle cooldown.
-                        
-                        for statement in &rule.execute {
-                            let _ignored = statement.eval(); // FIXME: Log errors
-                        }
+        let id = ScriptId(self.next_id);
+        self.next_id += 1;
+        self.scripts.lock().unwrap().insert(id, Arc::new(Mutex::new(task)));
+
+        {
+            let &(ref queue, ref cond) = &*self.run_queue;
+            queue.lock().unwrap().push_back(id);
+            cond.notify_one();
+        }
+
+        on_result(Ok(()));
+        Some(id)
+    }
+
+    /// Stop and forget about `id`. A no-op if it is not installed.
+    pub fn remove(&mut self, id: ScriptId) {
+        // It is fine if `id` is still sitting in `run_queue`: whichever
+        // worker eventually pops it will find it already gone from
+        // `scripts` and simply move on.
+        self.scripts.lock().unwrap().remove(&id);
+    }
+
+    /// Body of each worker thread: repeatedly pop a runnable script,
+    /// give it one quantum, and either drop it (once it stops), send it
+    /// straight to the back of the queue (it had real work to do), or
+    /// nap until it is actually worth revisiting (it was idle).
+    fn work(scripts: Arc<Mutex<HashMap<ScriptId, Arc<Mutex<ExecutionTask<Env>>>>>>,
+            run_queue: Arc<(Mutex<VecDeque<ScriptId>>, Condvar)>,
+            stop: Arc<AtomicBool>,
+            quantum: usize)
+    {
+        let &(ref queue, ref cond) = &*run_queue;
+        loop {
+            if stop.load(Ordering::Relaxed) {
+                return;
+            }
+
+            let id = {
+                let mut guard = queue.lock().unwrap();
+                while guard.is_empty() {
+                    if stop.load(Ordering::Relaxed) {
+                        return;
                     }
+                    guard = cond.wait(guard).unwrap();
+                }
+                guard.pop_front().unwrap()
+            };
+
+            // Clone out this script's own handle and release the map
+            // lock immediately, so another worker can look up its own
+            // assigned script (or `install`/`remove` can run) while this
+            // one spends a whole quantum on `task`.
+            let task = scripts.lock().unwrap().get(&id).cloned();
+            let outcome = match task {
+                None => QuantumOutcome::Stopped, // `remove()`d while it was queued.
+                Some(task) => task.lock().unwrap().run_quantum(quantum),
+            };
+
+            match outcome {
+                QuantumOutcome::Stopped => {
+                    scripts.lock().unwrap().remove(&id);
+                }
+                QuantumOutcome::Ran => {
+                    queue.lock().unwrap().push_back(id);
+                    cond.notify_one();
+                }
+                QuantumOutcome::Idle(deadline) => {
+                    // Nothing was ready: sleep until the script's own
+                    // next wake-up instead of immediately handing it
+                    // back to a free worker, which would otherwise peg
+                    // a full core spinning through no-op quanta. Capped
+                    // so `remove()`/newly-installed scripts are still
+                    // noticed reasonably promptly.
+                    let cap = StdDuration::from_millis(250);
+                    let nap = match deadline {
+                        Some(deadline) => (deadline - Env::runtime().now()).to_std().unwrap_or(StdDuration::new(0, 0)),
+                        None => cap,
+                    };
+                    thread::sleep(if nap > cap { cap } else { nap });
+                    queue.lock().unwrap().push_back(id);
+                    cond.notify_one();
                 }
             }
         }
     }
 }
 
+impl<Env> Drop for Supervisor<Env> where Env: RuntimeEnv + 'static {
+    /// Non-blocking: workers are spawned through `Env::runtime()`, which
+    /// (unlike `thread::spawn`) hands back no join handle, so shutdown
+    /// is purely cooperative -- flip `stop` and wake everyone blocked on
+    /// `run_queue` so each worker notices it on its own and returns.
+    /// `drop` returning is not a guarantee that every worker has already
+    /// stopped, only that they have all been told to.
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let &(_, ref cond) = &*self.run_queue;
+        cond.notify_all();
+    }
+}
+
 ///
 /// # Evaluating conditions
 ///
@@ -478,11 +1460,49 @@ impl<Env> Condition<CompiledCtx<Env>, Env> where Env: DevEnv {
     }
 }
 
+///
+/// # Evaluating expressions
+///
+
+/// A read-only view carried through a single `execute` batch so that
+/// `Expression::eval` can resolve `Expression::Input` to an actual
+/// value instead of panicking, rather than having `eval` reach for
+/// ambient globals.
+///
+/// Built once per firing, by `ExecutionTask::fire`, so that every
+/// statement (and every `Expression::Input` within it) judges
+/// staleness against the same instant.
+pub struct EvalCtx<Env> where Env: DevEnv {
+    now: DateTime<UTC>,
+    phantom: PhantomData<Env>,
+}
+
+impl<Env> EvalCtx<Env> where Env: DevEnv {
+    pub fn new(now: DateTime<UTC>) -> Self {
+        EvalCtx {
+            now: now,
+            phantom: PhantomData,
+        }
+    }
+}
+
 impl<Env> Statement<CompiledCtx<Env>, Env> where Env: ExecutableDevEnv {
-    fn eval(&self) -> Result<(), Error> {
-        let args = self.arguments.iter().map(|(k, v)| {
-            (k.clone(), v.eval())
-        }).collect();
+    fn eval(&self, ctx: &EvalCtx<Env>) -> Result<(), Error> {
+        let mut args = HashMap::with_capacity(self.arguments.len());
+        for (k, v) in &self.arguments {
+            match v.eval(ctx) {
+                Ok(value) => {
+                    args.insert(k.clone(), value);
+                }
+                Err(err) => {
+                    // One of the arguments isn't ready yet (or never
+                    // will be, depending on the error): skip the whole
+                    // statement rather than send a garbage/missing
+                    // argument to the device.
+                    return Err(err); // FIXME: Log this instead of silently dropping the statement.
+                }
+            }
+        }
         for output in &self.destination {
             Env::send(&output.device, &self.action, &args); // FIXME: Handle errors
         }
@@ -491,17 +1511,77 @@ impl<Env> Statement<CompiledCtx<Env>, Env> where Env: ExecutableDevEnv {
 }
 
 impl<Env> Expression<CompiledCtx<Env>, Env> where Env: ExecutableDevEnv {
-    fn eval(&self) -> Value {
+    fn eval(&self, ctx: &EvalCtx<Env>) -> Result<Value, Error> {
         match *self {
-            Expression::Value(ref v) => v.clone(),
-            Expression::Input(_) => panic!("Cannot read an input in an expression yet"),
+            Expression::Value(ref v) => Ok(v.clone()),
+            Expression::Input(ref input_set) => input_set.eval(ctx),
             Expression::Vec(ref vec) => {
-                Value::Vec(vec.iter().map(|expr| expr.eval()).collect())
+                let values = try!(vec.iter().map(|expr| expr.eval(ctx)).collect());
+                Ok(Value::Vec(values))
             }
         }
     }
 }
 
+impl<Env> InputSet<CompiledCtx<Env>, Env> where Env: ExecutableDevEnv {
+    /// Resolve this `InputSet` to a single `Value`, by collecting the
+    /// most recent non-stale reading of every matching input and
+    /// combining them with `self.reduction`.
+    fn eval(&self, ctx: &EvalCtx<Env>) -> Result<Value, Error> {
+        let mut values = Vec::new();
+        for single in &*self.condition.input {
+            // This will fail only if the thread has already panicked.
+            let state = single.state.read().unwrap();
+            if let Some(ref data) = *state {
+                if let Some(max_age) = self.max_age {
+                    if ctx.now - data.updated > max_age {
+                        continue; // Too stale to act on.
+                    }
+                }
+                values.push(data.data.clone());
+            }
+        }
+
+        if values.is_empty() {
+            return Err(Error::EvalError(EvalError::NoValueAvailable));
+        }
+
+        match self.reduction {
+            Reduction::First => Ok(values.into_iter().next().unwrap()),
+            Reduction::Min => reduce_numeric(values, |acc, x| if x < acc { x } else { acc }),
+            Reduction::Max => reduce_numeric(values, |acc, x| if x > acc { x } else { acc }),
+            Reduction::Mean => {
+                let mut sum = 0.;
+                let count = values.len();
+                for value in values {
+                    sum += try!(as_numeric(value));
+                }
+                Ok(Value::Num(sum / count as f64))
+            }
+        }
+    }
+}
+
+/// Extract the `f64` carried by a `Value::Num`, or a typed error if
+/// `value` isn't numeric.
+fn as_numeric(value: Value) -> Result<f64, Error> {
+    match value {
+        Value::Num(x) => Ok(x),
+        _ => Err(Error::EvalError(EvalError::NotNumeric)),
+    }
+}
+
+/// Shared implementation of `Reduction::Min`/`Reduction::Max`: fold
+/// `values` pairwise with `pick`, after checking they are all numeric.
+fn reduce_numeric<F>(values: Vec<Value>, pick: F) -> Result<Value, Error> where F: Fn(f64, f64) -> f64 {
+    let mut numbers = Vec::with_capacity(values.len());
+    for value in values {
+        numbers.push(try!(as_numeric(value)));
+    }
+    let first = numbers[0];
+    Ok(Value::Num(numbers.into_iter().skip(1).fold(first, pick)))
+}
+
 
 
 #[derive(Debug)]
@@ -510,10 +1590,101 @@ pub enum RunningError {
     NotRunning,
 }
 
+/// What can go wrong while evaluating an `Expression`.
+#[derive(Debug)]
+pub enum EvalError {
+    /// Every input matching an `InputSet` either has not produced a
+    /// value yet, or its latest value is older than the set's `max_age`.
+    NoValueAvailable,
+
+    /// `Reduction::Min`/`Max`/`Mean` was asked to combine a value that
+    /// isn't a `Value::Num`.
+    NotNumeric,
+}
+
 #[derive(Debug)]
 pub enum Error {
     CompileError(compile::Error),
     RunningError(RunningError),
+    EvalError(EvalError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A message sent before any `advance()` call is still noticed
+    /// promptly, without waiting for the virtual clock to move.
+    #[test]
+    fn recv_returns_a_pending_message_without_advancing() {
+        let runtime = DeterministicRuntime::new(UTC::now(), 0);
+        let (tx, rx) = channel();
+        tx.send(ExecutionOp::Tick).unwrap();
+
+        match runtime.recv(&rx, None) {
+            RecvOutcome::Message(ExecutionOp::Tick) => {}
+            _ => panic!("expected a pending Tick to come back immediately"),
+        }
+    }
+
+    /// `recv` genuinely blocks when nothing is pending: it must not
+    /// return before the virtual clock reaches `deadline`, no matter
+    /// how long the calling thread is left running.
+    #[test]
+    fn recv_blocks_until_the_deadline_is_advanced_past() {
+        let start = UTC::now();
+        let runtime = Arc::new(DeterministicRuntime::new(start, 0));
+        let (_tx, rx) = channel();
+
+        let waiting = Arc::new(AtomicBool::new(true));
+        let waiting_in_thread = waiting.clone();
+        let runtime_in_thread = runtime.clone();
+        let handle = thread::spawn(move || {
+            let outcome = runtime_in_thread.recv(&rx, Some(start + Duration::seconds(10)));
+            waiting_in_thread.store(false, Ordering::SeqCst);
+            outcome
+        });
+
+        // Give the other thread plenty of real time to (wrongly) return
+        // on its own; it must still be waiting, since the virtual clock
+        // hasn't moved.
+        thread::sleep(StdDuration::from_millis(50));
+        assert!(waiting.load(Ordering::SeqCst), "recv returned before its deadline elapsed");
+
+        runtime.advance(Duration::seconds(10));
+        match handle.join().unwrap() {
+            RecvOutcome::TimedOut => {}
+            _ => panic!("expected recv to time out once the deadline was advanced past"),
+        }
+    }
+
+    /// `spawn` must hand `f` to another thread rather than running it
+    /// inline -- otherwise a caller that spawns a long-running (or
+    /// infinite) job, e.g. `ExecutionTask::run`, would never get
+    /// control back.
+    #[test]
+    fn spawn_runs_concurrently_rather_than_inline() {
+        let runtime = DeterministicRuntime::new(UTC::now(), 0);
+        let done = Arc::new(AtomicBool::new(false));
+        let done_in_job = done.clone();
+
+        runtime.spawn(Box::new(move || {
+            thread::sleep(StdDuration::from_millis(50));
+            done_in_job.store(true, Ordering::SeqCst);
+        }));
+
+        // If `spawn` ran `f` synchronously, `done` would already be set
+        // by the time control returns here.
+        assert!(!done.load(Ordering::SeqCst), "spawn ran its job inline instead of concurrently");
+
+        for _ in 0..20 {
+            if done.load(Ordering::SeqCst) {
+                return;
+            }
+            thread::sleep(StdDuration::from_millis(20));
+        }
+        panic!("spawned job never ran");
+    }
 }
 
 